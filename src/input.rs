@@ -0,0 +1,57 @@
+use std::io::{BufRead, BufReader};
+
+/// A source of program input values, pulled by index. `GetInput`/`ForEach`
+/// go through this instead of a fixed `Vec<String>`, so a program can be
+/// driven from a lazily-read stream (stdin, a pipe) just as well as from
+/// a materialized argument list.
+pub trait InputSource {
+    /// Returns the value at `index`, reading further into the underlying
+    /// stream if it hasn't been reached yet. Returns `None` once the
+    /// source is exhausted.
+    fn get(&mut self, index: usize) -> Option<String>;
+}
+
+/// The original, fully-materialized input: command-line arguments.
+impl InputSource for Vec<String> {
+    fn get(&mut self, index: usize) -> Option<String> {
+        self.as_slice().get(index).cloned()
+    }
+}
+
+impl InputSource for Box<dyn InputSource> {
+    fn get(&mut self, index: usize) -> Option<String> {
+        (**self).get(index)
+    }
+}
+
+/// Wraps any `Iterator<Item = String>` (e.g. stdin read line-by-line) as
+/// a lazily-pulled, randomly-indexable [InputSource]. Values are read
+/// from the iterator only as far as the highest index requested so far,
+/// and buffered so an out-of-order index doesn't lose earlier ones.
+pub struct LazyInput<I: Iterator<Item = String>> {
+    iter: I,
+    buffered: Vec<String>,
+}
+
+impl<I: Iterator<Item = String>> LazyInput<I> {
+    pub fn new(iter: I) -> LazyInput<I> {
+        LazyInput { iter, buffered: Vec::new() }
+    }
+}
+
+impl<I: Iterator<Item = String>> InputSource for LazyInput<I> {
+    fn get(&mut self, index: usize) -> Option<String> {
+        while self.buffered.len() <= index {
+            self.buffered.push(self.iter.next()?);
+        }
+        Some(self.buffered[index].clone())
+    }
+}
+
+/// Wraps stdin as a lazily-read [InputSource], one value per line, for
+/// driving a program from a pipe instead of materializing all input
+/// up front.
+pub fn stdin_input() -> LazyInput<impl Iterator<Item = String>> {
+    let reader = BufReader::new(std::io::stdin());
+    LazyInput::new(reader.lines().map(|line| line.expect("Error reading from stdin")))
+}
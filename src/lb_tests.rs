@@ -12,8 +12,8 @@ macro_rules! assert_lb_out {
         let mut data = Storage::new();
         let mut out = String::new();
         let lex = LBT::lexer($x);
-        let inv = Vec::<String>::new();
-        let mut program = Program::new(lex, &mut data, &inv, &mut out, 1000).expect("Program init failed");
+        let mut inv = Vec::<String>::new();
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 1000).expect("Program init failed");
         let result = program.run();
         if let Err(msg) = result {
             panic!("Program failed: {}", msg);
@@ -32,8 +32,8 @@ macro_rules! assert_lb_from_input {
         let mut data = Storage::new();
         let mut out = String::new();
         let lex = LBT::lexer($x);
-        let inv = $y;
-        let mut program = Program::new(lex, &mut data, &inv, &mut out, 1000).expect("Program init failed");
+        let mut inv = $y;
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 1000).expect("Program init failed");
         let result = program.run();
         if let Err(msg) = result {
             panic!("Program failed: {}", msg);
@@ -200,6 +200,323 @@ mod math_ops {
     }
 }
 
+#[cfg(test)]
+mod functions {
+    use crate::storage::*;
+    use crate::program::*;
+    use crate::lexerbox::LBT;
+    use logos::Logos;
+
+    #[test]
+    fn define_and_call() {
+        assert_lb_out!("Dsqr'MMaaa' Sx4 Ksqr.ax Px", "16");
+        assert_lb_out!("Sa'Hi' Dgreet'Pa' Kgreet.", "Hi");
+    }
+
+    #[test]
+    fn prelude() {
+        assert_lb_out!("Sa5 Kdouble. Pa", "10");
+        assert_lb_out!("Sx5 Kinc.ax Px", "6");
+        assert_lb_out!("Sx5 Kzero.ax Px", "0");
+    }
+
+    #[test]
+    fn undefined_fn_errors() {
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("Kghost.");
+        let mut inv = Vec::<String>::new();
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 1000).expect("Program init failed");
+        let diag = program.run().expect_err("undefined function should fail");
+        assert_eq!(diag.error, LbError::UndefinedFn(String::from("ghost")));
+    }
+}
+
+#[cfg(test)]
+mod str_ops {
+    use crate::storage::*;
+    use crate::program::*;
+    use crate::lexerbox::LBT;
+    use logos::Logos;
+
+    #[test]
+    fn concat() {
+        assert_lb_out!("Sa'foo' Sb'bar' YCdab Pd", "foobar");
+        assert_lb_out!("Sa'' Sb'x' YCdab Pd", "x");
+    }
+
+    #[test]
+    fn length() {
+        assert_lb_out!("Sa'hello' YLba Pb", "5");
+        assert_lb_out!("Sa'' YLba Pb", "0");
+    }
+
+    #[test]
+    fn substring() {
+        assert_lb_out!("Sa'hello world' Sb6 Sc5 YSdabc Pd", "world");
+        assert_lb_out!("Sa'hello' Sb0 Sc3 YSdabc Pd", "hel");
+    }
+
+    #[test]
+    fn index() {
+        assert_lb_out!("Sa'hello world' Sb'world' YIcab Pc", "6");
+        assert_lb_out!("Sa'hello' Sb'xyz' YIcab Pc", "-1");
+    }
+}
+
+#[cfg(test)]
+mod foreach {
+    use crate::storage::*;
+    use crate::program::*;
+    use crate::lexerbox::LBT;
+    use logos::Logos;
+
+    #[test]
+    fn numeric_and_string_items() {
+        assert_lb_from_input!("ENiPa", vec!["1".to_string(), "2".to_string(), "3".to_string()], "123");
+        assert_lb_from_input!("ESiPa", vec!["foo".to_string(), "bar".to_string()], "foobar");
+    }
+
+    #[test]
+    fn index_var() {
+        assert_lb_from_input!("ESiPi", vec!["a".to_string(), "b".to_string(), "c".to_string()], "012");
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_lb_from_input!("ENiPa", Vec::<String>::new(), "");
+    }
+
+    #[test]
+    fn parse_number_error() {
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("ENiPa");
+        let mut inv = vec![String::from("notanumber")];
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 1000).expect("Program init failed");
+        let diag = program.run().expect_err("non-numeric input should fail to parse");
+        assert_eq!(diag.error, LbError::ParseNumber(String::from("notanumber")));
+    }
+}
+
+#[cfg(test)]
+mod parse_validation {
+    use crate::storage::*;
+    use crate::program::*;
+    use crate::parser::ParseError;
+    use crate::lexerbox::LBT;
+    use logos::Logos;
+
+    #[test]
+    fn rejects_invalid_token_before_running() {
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("Pa Zx Pb");
+        let mut inv = Vec::<String>::new();
+        let err = match Program::new(lex, &mut data, &mut inv, &mut out, 1000) {
+            Err(e) => e,
+            Ok(_) => panic!("malformed program should be rejected at construction"),
+        };
+        assert!(matches!(err.error, LbError::Parse(ParseError::InvalidToken(_))));
+        // rejected before a single instruction ran, so `out` never got written to
+        assert_eq!(out, "");
+    }
+
+    #[test]
+    fn rejects_invalid_token_nested_in_block() {
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("Wa[Pb Zx]");
+        let mut inv = Vec::<String>::new();
+        let err = match Program::new(lex, &mut data, &mut inv, &mut out, 1000) {
+            Err(e) => e,
+            Ok(_) => panic!("malformed nested token should be rejected at construction"),
+        };
+        assert!(matches!(err.error, LbError::Parse(ParseError::InvalidToken(_))));
+    }
+}
+
+#[cfg(test)]
+mod diagnostics {
+    use crate::storage::*;
+    use crate::program::*;
+    use crate::lexerbox::LBT;
+    use logos::Logos;
+
+    #[test]
+    fn points_at_the_failing_instruction() {
+        // error on the second line, at column 1
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("Sa1 Pa\nGNb5");
+        let mut inv = Vec::<String>::new();
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 1000).expect("Program init failed");
+        let diag = program.run().expect_err("GetInput past end of input should fail");
+        assert_eq!(diag.error, LbError::NoInputAt(5));
+        let loc = diag.loc.expect("runtime error should carry a source location");
+        assert_eq!(loc.line, 2);
+        assert_eq!(loc.column, 1);
+        assert_eq!(loc.snippet, "GNb5");
+
+        // error mid-line, at column 4
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("Pa GNb5");
+        let mut inv = Vec::<String>::new();
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 1000).expect("Program init failed");
+        let diag = program.run().expect_err("GetInput past end of input should fail");
+        let loc = diag.loc.expect("runtime error should carry a source location");
+        assert_eq!(loc.line, 1);
+        assert_eq!(loc.column, 4);
+    }
+}
+
+#[cfg(test)]
+mod text_ops {
+    use crate::storage::*;
+    use crate::program::*;
+    use crate::lexerbox::LBT;
+    use logos::Logos;
+
+    #[test]
+    fn case_and_reverse() {
+        assert_lb_out!("Sa'Hello' TUba Pb", "HELLO");
+        assert_lb_out!("Sa'Hello' TLba Pb", "hello");
+        assert_lb_out!("Sa'Hello' TRba Pb", "olleH");
+    }
+
+    #[test]
+    fn length_and_substring() {
+        assert_lb_out!("Sa'Hello' TNba Pb", "5");
+        assert_lb_out!("Sa'Hello World' Sb6 Sc5 TSdabc Pd", "World");
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        // "Hi" is 2 bytes, not a multiple of 3, so this exercises padding too
+        assert_lb_out!("Sa'Hi' TEba Pb", "SGk=");
+        assert_lb_out!("Sa'SGVsbG8=' TDba Pb", "Hello");
+        assert_lb_out!("Sa'Hello, world!' TEba TDcb Pc", "Hello, world!");
+    }
+
+    #[test]
+    fn base64_decode_errors() {
+        // length not a multiple of 4
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("Sa'QQ=' TDba Pb");
+        let mut inv = Vec::<String>::new();
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 1000).expect("Program init failed");
+        let diag = program.run().expect_err("malformed-length Base64 should fail");
+        assert_eq!(diag.error, LbError::InvalidBase64(String::from("QQ=")));
+
+        // padding in an earlier group, not just the final one
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("Sa'QQ==AAAA' TDba Pb");
+        let mut inv = Vec::<String>::new();
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 1000).expect("Program init failed");
+        let diag = program.run().expect_err("padding before the final group should fail");
+        assert_eq!(diag.error, LbError::InvalidBase64(String::from("QQ==AAAA")));
+
+        // non-alphabet character
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("Sa'abc$' TDba Pb");
+        let mut inv = Vec::<String>::new();
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 1000).expect("Program init failed");
+        let diag = program.run().expect_err("non-alphabet character should fail");
+        assert_eq!(diag.error, LbError::InvalidBase64(String::from("abc$")));
+    }
+}
+
+#[cfg(test)]
+mod input_source {
+    use crate::storage::*;
+    use crate::program::*;
+    use crate::input::{InputSource, LazyInput};
+    use crate::lexerbox::LBT;
+    use logos::Logos;
+
+    #[test]
+    fn escape_sequences_unescape_in_output() {
+        let expected = format!("{}{}{}{}", "tab:\there", "nl:\n", "quote:'", "backslash:\\");
+        assert_lb_out!(r"P'tab:\there' P'nl:\n' P'quote:\'' P'backslash:\\'", expected);
+    }
+
+    #[test]
+    fn lazy_input_matches_vec_input() {
+        let items = vec![String::from("1"), String::from("2")].into_iter();
+        assert_lb_from_input!("Sa4 Pa", LazyInput::new(items), "4");
+        let items = vec![String::from("1"), String::from("2")].into_iter();
+        assert_lb_from_input!("GNa0 GNb1 MAcab Pa Pb Pc", LazyInput::new(items), "123");
+    }
+
+    #[test]
+    fn lazy_input_errors_past_end_of_stream() {
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("GNa5 Pa");
+        let mut inv = LazyInput::new(vec![String::from("1")].into_iter());
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 1000).expect("Program init failed");
+        let diag = program.run().expect_err("GetInput past the end of a lazy stream should fail");
+        assert_eq!(diag.error, LbError::NoInputAt(5));
+    }
+
+    #[test]
+    fn boxed_input_source_works_like_its_wrapped_source() {
+        let items = vec![String::from("Pizza")].into_iter();
+        let boxed: Box<dyn InputSource> = Box::new(LazyInput::new(items));
+        assert_lb_from_input!("GSa0 Pa", boxed, "Pizza");
+    }
+}
+
+#[cfg(test)]
+mod recursion {
+    use crate::storage::*;
+    use crate::program::*;
+    use crate::lexerbox::LBT;
+    use logos::Logos;
+
+    #[test]
+    fn named_functions_call_each_other() {
+        assert_lb_out!("Db'Pa' Da'Kb.' Ka.", "0");
+    }
+
+    #[test]
+    fn deep_call_fn_recursion_terminates_with_a_limit_error() {
+        // A function that calls itself forever must still terminate
+        // promptly (bounded by `loop_limit`) instead of hanging; the
+        // shared loop/recursion budget (see `consume_budget`) is what
+        // catches this, ahead of the separate depth guard.
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("Dloopy'Kloopy.' Kloopy.");
+        let mut inv = Vec::<String>::new();
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 5).expect("Program init failed");
+        let diag = program.run().expect_err("infinite self-recursion should not run forever");
+        assert_eq!(diag.error, LbError::LimitExceeded);
+    }
+
+    #[test]
+    fn deep_execute_recursion_terminates_with_a_limit_error() {
+        let mut data = Storage::new();
+        let mut out = String::new();
+        let lex = LBT::lexer("Sa'Xa' Xa");
+        let mut inv = Vec::<String>::new();
+        let mut program = Program::new(lex, &mut data, &mut inv, &mut out, 5).expect("Program init failed");
+        let diag = program.run().expect_err("infinite Execute self-recursion should not run forever");
+        assert_eq!(diag.error, LbError::LimitExceeded);
+    }
+
+    #[test]
+    fn recursion_limit_reports_the_call_chain() {
+        let stack = vec![String::from("a"), String::from("b"), String::from("c")];
+        let err = LbError::RecursionLimit(stack);
+        assert_eq!(format!("{}", err), "Recursion limit exceeded: a -> b -> c");
+    }
+}
+
 #[cfg(test)]
 mod bool_ops {
     use crate::storage::*;
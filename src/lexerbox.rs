@@ -11,9 +11,9 @@ pub enum LBT {
     SaveNumber((char, f64)),
 
     /// Save a value into a variable
-    /// 
-    /// Usage: `S'hello'`
-    #[regex(r"S[a-z]'[^']*'", save_str)]
+    ///
+    /// Usage: `Sa'hello'`. Supports `\n`, `\t`, `\\`, and `\'` escapes.
+    #[regex(r"S[a-z]'([^'\\]|\\.)*'", save_str)]
     SaveStr((char, String)),
 
     /// Copy the value of a variable into another.
@@ -29,9 +29,9 @@ pub enum LBT {
     PrintVar(char),
 
     /// Print the given string directly. Doesn't save it into storage.
-    /// 
-    /// Usage: `P'hello world'`
-    #[regex(r"P'[^']*'", print_str)]
+    ///
+    /// Usage: `P'hello world'`. Supports `\n`, `\t`, `\\`, and `\'` escapes.
+    #[regex(r"P'([^'\\]|\\.)*'", print_str)]
     PrintStr(String),
 
     /// Performs a mathematical operation.
@@ -46,23 +46,26 @@ pub enum LBT {
     #[regex(r"B[A-Z][a-z][a-z][a-z]", bool_op)]
     BoolOp((char, char, char, char)),
 
-    /// Performs command X, a times
-    /// 
+    /// Performs command X, a times. X can be a single subcommand, or a
+    /// bracket-delimited block of several (`La[Pb Pc]`).
+    ///
     /// Usage: `LaX`
-    #[regex(r"L[a-z][A-Za-z]+", base_loop)]
-    Loop((char, Box<LBT>)),
+    #[regex(r"L[a-z]", base_loop)]
+    Loop((char, Vec<LBT>)),
 
-    /// If a is nonzero, perform command X
-    /// 
+    /// If a is nonzero, perform command X. X can be a single subcommand, or
+    /// a bracket-delimited block of several (`Ia[Pb Pc]`).
+    ///
     /// Usage: `IaX`
-    #[regex(r"I[a-z][A-Za-z]+", base_loop)]
-    IfStatement((char, Box<LBT>)),
+    #[regex(r"I[a-z]", base_loop)]
+    IfStatement((char, Vec<LBT>)),
 
-    /// While a is nonzero, repeat command X
-    /// 
+    /// While a is nonzero, repeat command X. X can be a single subcommand,
+    /// or a bracket-delimited block of several (`Wa[Pb Pc]`).
+    ///
     /// Usage: `WaX`
-    #[regex(r"W[a-z][A-Za-z]+", base_loop)]
-    WhileLoop((char, Box<LBT>)),
+    #[regex(r"W[a-z]", base_loop)]
+    WhileLoop((char, Vec<LBT>)),
 
     /// Reset variable a to 0.
     /// 
@@ -77,11 +80,20 @@ pub enum LBT {
     ResetAll,
 
     /// Gets nth input and stores it in variable a as type X (N or S)
-    /// 
+    ///
     /// Usage: `GXa1`
     #[regex(r"G[A-Z][a-z][0-9]+", get_input)]
     GetInput((char, char, f64)),
 
+    /// Iterates over every program input argument, parsing each one as type
+    /// X (N or S) and binding it to variable `a`, with the current index
+    /// bound to the given variable, then runs subcommand Y once per element.
+    /// Y follows the index variable directly, with no space in between.
+    ///
+    /// Usage: `ENiY`
+    #[regex(r"E[A-Z][a-z][A-Za-z]+", foreach_loop)]
+    ForEach((char, char, Box<LBT>)),
+
     /// If a is nonzero, set it to 0, else set it to 1.
     /// 
     /// Usage: `Na`
@@ -96,11 +108,52 @@ pub enum LBT {
 
     /// Executes a string value as a Letterbox program.
     /// Replaces any number of parameters with different variables.
-    /// 
+    ///
     /// Usage: `Xzacbd`
     #[regex(r"X[a-z]([a-z][a-z])*", execute_var)]
     Execute((char, String)),
 
+    /// Performs a string operation, reading `Val::Text` operands from
+    /// storage and writing the result back.
+    ///
+    /// `C`oncat: `YCdab` sets `d` to `a` followed by `b`.
+    /// `L`ength: `YLda` sets `d` to the character length of `a`.
+    /// `S`ubstring: `YSdabc` sets `d` to the substring of `a` starting
+    /// at (numeric) `b` for (numeric) `c` characters.
+    /// `I`ndex: `YIdab` sets `d` to the character index of `b` within
+    /// `a`, or `-1` if `b` isn't found in `a`.
+    #[regex(r"Y[A-Z][a-z]+", str_op)]
+    StrOp((char, Vec<char>)),
+
+    /// Performs a text transform, reading a `Val::Text` operand from
+    /// storage and writing the result back. Implemented in `Storage`
+    /// rather than `Program::evaluate`, since every op here just reads
+    /// and writes storage with no control-flow or I/O involved.
+    ///
+    /// `U`ppercase / `L`owercase / `R`everse: `Tdst src`.
+    /// `N`: `dst` := the character length of `src`.
+    /// `S`ubstring: `Tdst src start len` sets `dst` to the substring of
+    /// `src` starting at (numeric) `start` for (numeric) `len` characters.
+    /// `E`ncode / `D`ecode: `dst` := the standard-alphabet Base64
+    /// encoding (or decoding) of `src`.
+    #[regex(r"T[A-Z][a-z]+", text_op)]
+    TextOp((char, Vec<char>)),
+
+    /// Defines a named, multi-character subprogram that can later be
+    /// invoked with `CallFn`. Unlike `Execute`, this isn't limited to a
+    /// single-char variable slot.
+    ///
+    /// Usage: `Dgreet'P\'Hello\''`
+    #[regex(r"D[a-z]+'([^'\\]|\\.)*'", define_fn)]
+    DefineFn((String, String)),
+
+    /// Calls a previously-defined (or prelude'd) named function, remapping
+    /// any number of parameters with different variables, same as `Execute`.
+    ///
+    /// Usage: `Kgreet.` or `Kdoublea.acbd`
+    #[regex(r"K[a-z]+\.([a-z][a-z])*", call_fn)]
+    CallFn((String, String)),
+
     /// Unrecognized character(s)
     #[error]
     // skip comments
@@ -128,8 +181,9 @@ fn save_number(lex: &mut Lexer<LBT>) -> Option<(char, f64)> {
 fn save_str(lex: &mut Lexer<LBT>) -> Option<(char, String)> {
     let token = lex.slice();
     let var_name = token.chars().nth(1);
-    let my_str = String::from(token[2..].trim_matches('\''));
-    
+    let quoted = &token[2..];
+    let my_str = unescape_str(&quoted[1..quoted.len() - 1]);
+
     match var_name {
         Some(var) => Some((var, my_str)),
         None => None,
@@ -156,7 +210,8 @@ fn single_var_arg(lex: &mut Lexer<LBT>) -> Option<char> {
 
 fn print_str(lex: &mut Lexer<LBT>) -> Option<String> {
     let token = lex.slice();
-    let my_str = String::from(token[1..].trim_matches('\''));
+    let quoted = &token[1..];
+    let my_str = unescape_str(&quoted[1..quoted.len() - 1]);
     Some(my_str)
 }
 
@@ -190,21 +245,64 @@ fn bool_op(lex: &mut Lexer<LBT>) -> Option<(char, char, char, char)> {
     Some((args[0], args[1], args[2], args[3]))
 }
 
-fn base_loop(lex: &mut Lexer<LBT>) -> Option<(char, Box<LBT>)> {
+/// Parses the body of `Loop`/`IfStatement`/`WhileLoop`, which follows the
+/// condition variable and is either a single subcommand (legacy form) or a
+/// `[...]` bracket-delimited sequence of commands. The body isn't part of
+/// this token's regex (its length isn't regular, since brackets nest), so
+/// this callback scans `lex.remainder()` by hand and `lex.bump`s past
+/// whatever it consumes.
+fn base_loop(lex: &mut Lexer<LBT>) -> Option<(char, Vec<LBT>)> {
     let token = lex.slice();
-    if let Some(condition) = token.chars().nth(1) {
-        let cmd_string: String = token[2..].chars().collect();
-        // must provide SOME subcommand
-        if cmd_string.len() <= 0 {
-            return None;
+    let condition = token.chars().nth(1)?;
+
+    let remainder = lex.remainder();
+    let after_ws = remainder.trim_start();
+    let ws_len = remainder.len() - after_ws.len();
+
+    if let Some(block) = after_ws.strip_prefix('[') {
+        // scan for the matching closing bracket, tracking nesting depth so
+        // a bracketed body can itself contain bracketed bodies. Skips over
+        // `'...'` string literal contents (honoring `\'`/`\\` escapes, same
+        // as the string-literal regexes above) so a literal `[`/`]` inside
+        // a quoted string doesn't throw off the depth count.
+        let mut depth = 1;
+        let mut close_byte = None;
+        let mut in_string = false;
+        let mut chars = block.char_indices();
+        while let Some((i, c)) = chars.next() {
+            if in_string {
+                match c {
+                    '\\' => { chars.next(); },
+                    '\'' => in_string = false,
+                    _ => {},
+                }
+                continue;
+            }
+            match c {
+                '\'' => in_string = true,
+                '[' => depth += 1,
+                ']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close_byte = Some(i);
+                        break;
+                    }
+                },
+                _ => {},
+            }
         }
-        let cmd = lex_sub(cmd_string);
-        return match cmd {
-            Some(subcommand) => Some((condition, Box::new(subcommand))),
-            None => None,
-        };
+        let close_byte = close_byte?;
+        let commands: Vec<LBT> = LBT::lexer(&block[..close_byte]).collect();
+        lex.bump(ws_len + 1 + close_byte + 1); // '[' + body + ']'
+        Some((condition, commands))
+    }
+    else {
+        // legacy form: exactly one subcommand, no brackets
+        let mut sub_lex = LBT::lexer(after_ws);
+        let subcommand = sub_lex.next()?;
+        lex.bump(ws_len + sub_lex.span().end);
+        Some((condition, vec![subcommand]))
     }
-    None
 }
 
 fn execute_var(lex: &mut Lexer<LBT>) -> Option<(char, String)> {
@@ -216,6 +314,65 @@ fn execute_var(lex: &mut Lexer<LBT>) -> Option<(char, String)> {
     None
 }
 
+fn str_op(lex: &mut Lexer<LBT>) -> Option<(char, Vec<char>)> {
+    let token = lex.slice();
+    let valid_ops = "CLSI";
+    let mut chars = token[1..].chars();
+    let op = chars.next()?;
+    if !valid_ops.contains(op) {
+        return None;
+    }
+    let args: Vec<char> = chars.collect();
+    let expected_len = match op {
+        'C' => 3, // dst, a, b
+        'L' => 2, // dst, src
+        'S' => 4, // dst, src, start, len
+        'I' => 3, // dst, haystack, needle
+        _ => return None,
+    };
+    if args.len() != expected_len {
+        return None;
+    }
+    Some((op, args))
+}
+
+fn text_op(lex: &mut Lexer<LBT>) -> Option<(char, Vec<char>)> {
+    let token = lex.slice();
+    let valid_ops = "ULRNSED";
+    let mut chars = token[1..].chars();
+    let op = chars.next()?;
+    if !valid_ops.contains(op) {
+        return None;
+    }
+    let args: Vec<char> = chars.collect();
+    let expected_len = match op {
+        'S' => 4, // dst, src, start, len
+        _ => 2,   // dst, src
+    };
+    if args.len() != expected_len {
+        return None;
+    }
+    Some((op, args))
+}
+
+fn define_fn(lex: &mut Lexer<LBT>) -> Option<(String, String)> {
+    let token = lex.slice();
+    let rest = &token[1..];
+    let quote_pos = rest.find('\'')?;
+    let name = rest[..quote_pos].to_string();
+    let body = unescape_str(&rest[quote_pos + 1..rest.len() - 1]);
+    Some((name, body))
+}
+
+fn call_fn(lex: &mut Lexer<LBT>) -> Option<(String, String)> {
+    let token = lex.slice();
+    let rest = &token[1..];
+    let dot_pos = rest.find('.')?;
+    let name = rest[..dot_pos].to_string();
+    let argmap: String = rest[dot_pos + 1..].chars().collect();
+    Some((name, argmap))
+}
+
 fn get_input(lex: &mut Lexer<LBT>) -> Option<(char, char, f64)> {
     let token = lex.slice();
     let valid_ops = "NS";
@@ -232,6 +389,26 @@ fn get_input(lex: &mut Lexer<LBT>) -> Option<(char, char, f64)> {
     Some((op, var, num.unwrap()))
 }
 
+fn foreach_loop(lex: &mut Lexer<LBT>) -> Option<(char, char, Box<LBT>)> {
+    let token = lex.slice();
+    let valid_types = "NS";
+    let op = token.chars().nth(1)?;
+    if !valid_types.contains(op) {
+        return None;
+    }
+    let index_var = token.chars().nth(2)?;
+    let cmd_string: String = token[3..].chars().collect();
+    // must provide SOME subcommand
+    if cmd_string.is_empty() {
+        return None;
+    }
+    let cmd = lex_sub(cmd_string);
+    match cmd {
+        Some(subcommand) => Some((op, index_var, Box::new(subcommand))),
+        None => None,
+    }
+}
+
 // Utilities
 
 /// Opens a new lexer to lex a subcommand.
@@ -241,6 +418,31 @@ fn lex_sub(sub: String) -> Option<LBT> {
     return lex.next();
 }
 
+/// Resolves `\n`, `\t`, `\\`, and `\'` escapes inside a quoted string literal.
+/// An unrecognized escape keeps its backslash, so `\x` stays `\x`.
+fn unescape_str(raw: &str) -> String {
+    let mut result = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('\\') => result.push('\\'),
+            Some('\'') => result.push('\''),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            },
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
 #[test]
 fn tokens_parse_correctly() {
     let mut lex = LBT::lexer("Sa4.4 Cab P'hello world' Pa i ! This is a comment".trim());
@@ -265,16 +467,60 @@ fn advanced_tokens() {
     assert_eq!(lex.next(), Some(LBT::ResetAll));
     assert_eq!(lex.slice(), "RA");
     assert_eq!(lex.next(), Some(
-        LBT::WhileLoop(('a', Box::new(
-            LBT::IfStatement(('c', Box::new(
+        LBT::WhileLoop(('a', vec![
+            LBT::IfStatement(('c', vec![
                 LBT::Execute(('z', String::from("abcd")))
-            )))
-        )))
+            ]))
+        ]))
     ));
     assert_eq!(lex.slice(), "WaIcXzabcd");
     assert_eq!(lex.next(), None);
 }
 
+#[test]
+fn block_bodies() {
+    let mut lex = LBT::lexer("Wa[MSaab Pa Pb] Pc".trim());
+    assert_eq!(lex.next(), Some(
+        LBT::WhileLoop(('a', vec![
+            LBT::MathOp(('S', 'a', 'a', 'b')),
+            LBT::PrintVar('a'),
+            LBT::PrintVar('b'),
+        ]))
+    ));
+    assert_eq!(lex.slice(), "Wa[MSaab Pa Pb]");
+    assert_eq!(lex.next(), Some(LBT::PrintVar('c')));
+    assert_eq!(lex.next(), None);
+}
+
+#[test]
+fn nested_block_bodies() {
+    let mut lex = LBT::lexer("Ia[Ib[Pc] Pd]".trim());
+    assert_eq!(lex.next(), Some(
+        LBT::IfStatement(('a', vec![
+            LBT::IfStatement(('b', vec![LBT::PrintVar('c')])),
+            LBT::PrintVar('d'),
+        ]))
+    ));
+    assert_eq!(lex.next(), None);
+}
+
+#[test]
+fn block_bodies_with_bracket_chars_in_strings() {
+    // the `[`/`]` inside the quoted strings must not be mistaken for
+    // nesting brackets by the block scanner
+    let mut lex = LBT::lexer("Wa[P'[' Pb P']'] Pc".trim());
+    assert_eq!(lex.next(), Some(
+        LBT::WhileLoop(('a', vec![
+            LBT::PrintStr(String::from("[")),
+            LBT::PrintVar('b'),
+            LBT::PrintStr(String::from("]")),
+        ]))
+    ));
+    assert_eq!(lex.slice(), "Wa[P'[' Pb P']']");
+    assert_eq!(lex.next(), Some(LBT::PrintVar('c')));
+    assert_eq!(lex.next(), None);
+}
+
 #[test]
 fn multi_line_comments() {
     let mut lex = LBT::lexer("! This program prints out n fibonacci numbers.
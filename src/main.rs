@@ -3,13 +3,13 @@
 // by Chris Natcharian
 
 use std::fs;
-use std::io;
-use std::io::Write;
 use std::process::exit;
 
 mod program;
 mod storage;
 mod lexerbox;
+mod parser;
+mod input;
 
 #[cfg(test)]
 mod lb_tests;
@@ -17,9 +17,15 @@ mod lb_tests;
 use crate::program::Program;
 use crate::storage::Storage;
 use crate::lexerbox::LBT;
+use crate::input::{stdin_input, InputSource};
 use clap::Command;
 use logos::{Logos, Lexer};
 use clap::{Arg, ArgAction, arg};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+/// Where the REPL's line history is persisted between sessions.
+const HISTORY_FILE: &str = ".letterbox_history";
 
 fn main() {
     // parse command line args
@@ -38,7 +44,16 @@ fn main() {
         .arg(Arg::new("PROGRAM-ARGS")
             .help("Pass arguments to your program")
             .long_help("Any arguments you provide will be available to your Letterbox program.")
-            .action(ArgAction::Append))
+            .action(ArgAction::Append)
+            .conflicts_with("stdin"))
+        .arg(arg!(-s --stdin)
+            .required(false)
+            .requires("file")
+            .help("Read program input from stdin instead of PROGRAM-ARGS")
+            .long_help("Read program input from stdin, one value per line, instead of from \
+                PROGRAM-ARGS. Values are pulled lazily as the program requests them, so this \
+                works with a pipe of unbounded length. Only valid alongside --file.")
+            .action(ArgAction::SetTrue))
         .get_matches();
 
     // extract program args
@@ -57,31 +72,45 @@ fn main() {
         exit(0);
     };
 
+    let use_stdin = matches.get_flag("stdin");
+
     // get filepath from args; if no filepath, open a command prompt
     match matches.get_one::<String>("file") {
-        Some(file_path) => run_program_from_file(file_path.to_owned(), loop_limit, args),
+        Some(file_path) => run_program_from_file(file_path.to_owned(), loop_limit, args, use_stdin),
         None => run_command_line(loop_limit, args),
     }
 }
 
 /// Reads the file at the given path. If it contains text, runs it as a Letterbox program.
-fn run_program_from_file(file_path: String, loop_limit: usize, args: Vec<String>) {
+/// Program input normally comes from `args`, but if `use_stdin` is set it's instead
+/// pulled lazily, one value per line, from stdin.
+fn run_program_from_file(file_path: String, loop_limit: usize, args: Vec<String>, use_stdin: bool) {
     // read file at filepath
     let program_string = fs::read_to_string(file_path).expect("Problem reading file");
-    
+
     // println!("File contents:\n{}", program_string);
 
     let lex: Lexer<LBT> = LBT::lexer(program_string.trim());
     let mut data = Storage::new();
-    let input_vec = args.to_owned();
+    let mut input: Box<dyn InputSource> = if use_stdin {
+        Box::new(stdin_input())
+    } else {
+        Box::new(args)
+    };
     let mut output_buffer = String::new();
-    let mut program = Program::new(
+    let mut program = match Program::new(
         lex,
         &mut data,
-        &input_vec,
+        &mut input,
         &mut output_buffer,
         loop_limit
-    ).expect("Error initializing program");
+    ) {
+        Ok(program) => program,
+        Err(diag) => {
+            println!("Error: {}", diag);
+            return;
+        },
+    };
 
     // println!("Program contents:\n{:?}", program.program_list);
     let program_result = program.run();
@@ -91,34 +120,78 @@ fn run_program_from_file(file_path: String, loop_limit: usize, args: Vec<String>
     }
 }
 
+/// Path to the REPL history file in the user's home directory, falling
+/// back to a relative path if the home directory can't be found.
+fn history_path() -> std::path::PathBuf {
+    match std::env::var("HOME") {
+        Ok(home) => std::path::Path::new(&home).join(HISTORY_FILE),
+        Err(_) => std::path::PathBuf::from(HISTORY_FILE),
+    }
+}
+
 /// Begins a loop in which the user can enter and execute Letterbox statements.
-/// Lasts until Ctrl+C is pressed or `quit` is entered.
+/// Lasts until Ctrl+C/Ctrl+D is pressed or `quit` is entered.
+/// Supports up/down history recall and in-line editing via `rustyline`,
+/// plus a few meta-commands (`:vars`, `:reset`) handled before lexing.
 fn run_command_line(loop_limit: usize, args: Vec<String>) {
 
-    // Establish a single data storage.
+    // Establish a single data storage that persists across entries.
     let mut total_storage = Storage::new();
+    let mut input = args;
 
-    loop {
-        // Collect line of program from input.
-        let mut line = String::new();
-        print!("> ");
-        io::stdout().flush().expect("Failed to flush to stdout."); // makes sure '>' is printed before pausing for input
-        io::stdin().read_line(&mut line).expect("Failed to read from stdin.");
+    let history_file = history_path();
+    let mut editor = DefaultEditor::new().expect("Failed to start line editor");
+    let _ = editor.load_history(&history_file);
 
-        // if special command "quit" has been typed, exit the loop.
-        if line.trim().to_lowercase() == "quit" { break; }
+    loop {
+        let readline = editor.readline("> ");
+        let line = match readline {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                println!("Error reading input: {err}");
+                break;
+            },
+        };
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() { continue; }
+
+        let _ = editor.add_history_entry(trimmed);
+
+        // Meta-commands are handled before lexing and never touch the program list.
+        match trimmed.to_lowercase().as_str() {
+            "quit" => break,
+            ":vars" => {
+                for (name, val) in total_storage.vars() {
+                    println!("{name} = {val}");
+                }
+                continue;
+            },
+            ":reset" => {
+                total_storage.reset_all().expect("Failed to reset storage");
+                continue;
+            },
+            _ => {},
+        }
 
-        // Define output buffers for the line.
+        // Define output buffer for the line.
         let mut line_output = String::new();
 
         // Lex and parse the line by creating a new Program instance referencing the Storage.
-        let lex = LBT::lexer(line.trim());
-        let mut program = Program::new(lex,
+        let lex = LBT::lexer(trimmed);
+        let mut program = match Program::new(lex,
             &mut total_storage,
-            &args,
+            &mut input,
             &mut line_output,
             loop_limit
-        ).expect("Error parsing line.");
+        ) {
+            Ok(program) => program,
+            Err(diag) => {
+                println!("Error: {}", diag);
+                continue;
+            },
+        };
 
         // Execute line until it finishes.
         let line_result = program.run();
@@ -131,5 +204,7 @@ fn run_command_line(loop_limit: usize, args: Vec<String>) {
             println!("Error: {}", msg);
         }
     }
+
+    let _ = editor.save_history(&history_file);
 }
 
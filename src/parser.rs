@@ -0,0 +1,106 @@
+use core::fmt;
+use std::ops::Range;
+
+use crate::lexerbox::LBT;
+
+/// A byte span in some program's source text, resolved to a human-facing
+/// line/column and the source line itself so an error can be reported the
+/// way a parser-combinator crate would, instead of as a bare message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLoc {
+    /// The byte range in `source` that the error applies to.
+    pub span: Range<usize>,
+    /// 1-indexed line number.
+    pub line: usize,
+    /// 1-indexed column number (byte-based, matching `span`).
+    pub column: usize,
+    /// The full text of the source line the span starts on.
+    pub snippet: String,
+}
+
+impl SourceLoc {
+    /// Resolves a byte span against the source text it came from.
+    pub fn new(source: &str, span: Range<usize>) -> SourceLoc {
+        let start = span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let line = source[..start].matches('\n').count() + 1;
+        let column = start - line_start + 1;
+
+        SourceLoc {
+            span,
+            line,
+            column,
+            snippet: source[line_start..line_end].to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SourceLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let caret = " ".repeat(self.column.saturating_sub(1)) + "^";
+        write!(f, "line {}, column {}:\n  {}\n  {}", self.line, self.column, self.snippet, caret)
+    }
+}
+
+/// A parse-time validation failure. Distinct from [crate::program::LbError]
+/// in that it's always surfaced by [crate::program::Program::new] before a
+/// single instruction has run, rather than partway through execution.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// A token didn't match any instruction's grammar, at this location.
+    /// For a token nested inside a `Loop`/`IfStatement`/`WhileLoop`/
+    /// `ForEach` body, the location is that of the enclosing block, since
+    /// bodies are re-lexed from a substring and don't carry their own
+    /// absolute spans.
+    InvalidToken(SourceLoc),
+}
+
+impl ParseError {
+    /// The source location this error applies to.
+    pub fn loc(&self) -> &SourceLoc {
+        match self {
+            ParseError::InvalidToken(loc) => loc,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidToken(_) => write!(f, "Invalid or malformed instruction"),
+        }
+    }
+}
+
+/// Validates a freshly-lexed token stream before it's ever run.
+///
+/// The lexer already rejects bad arity/operators for most instructions at
+/// match time (see `math_op`/`bool_op`/`get_input` in [crate::lexerbox]),
+/// falling back to [LBT::Error] when nothing else matches. Previously that
+/// `Error` token flowed straight into `evaluate`, so a malformed program
+/// could run for a while before failing (or never fail, if the bad token
+/// was never reached). This walks the whole tree up front instead,
+/// including nested blocks, so a malformed program is rejected before
+/// any side effect happens. `spans` holds one byte range per entry in
+/// `tokens`, as captured by the caller while lexing.
+pub fn parse(tokens: Vec<LBT>, spans: &[Range<usize>], source: &str) -> Result<Vec<LBT>, ParseError> {
+    for (token, span) in tokens.iter().zip(spans.iter()) {
+        validate_token(token, span, source)?;
+    }
+    Ok(tokens)
+}
+
+fn validate_token(token: &LBT, span: &Range<usize>, source: &str) -> Result<(), ParseError> {
+    match token {
+        LBT::Error => Err(ParseError::InvalidToken(SourceLoc::new(source, span.clone()))),
+        LBT::Loop((_, body)) | LBT::IfStatement((_, body)) | LBT::WhileLoop((_, body)) => {
+            for inner in body {
+                validate_token(inner, span, source)?;
+            }
+            Ok(())
+        },
+        LBT::ForEach((_, _, body)) => validate_token(body, span, source),
+        _ => Ok(()),
+    }
+}
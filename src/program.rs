@@ -1,6 +1,12 @@
 use core::fmt;
+use std::cell::Cell;
+use std::ops::Range;
+use std::rc::Rc;
 use regex::Regex;
 
+use crate::input::InputSource;
+use crate::parser;
+use crate::parser::{ParseError, SourceLoc};
 use crate::storage;
 use crate::storage::Storage;
 use logos::{Lexer, Logos};
@@ -32,18 +38,93 @@ impl fmt::Display for Val {
     }
 }
 
+/// A structured error produced by a Letterbox program.
+/// Replaces ad-hoc `String` errors so failures can be matched on and
+/// uniformly formatted instead of panicking the whole process.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LbError {
+    /// A variable name isn't a valid Letterbox identifier (`a`-`z`).
+    UndefinedVar(char),
+    /// A variable held the wrong kind of [Val] for the operation.
+    TypeMismatch { var: char, expected: &'static str },
+    /// An instruction's operator character wasn't one of its valid ops.
+    InvalidOp { kind: &'static str, op: char },
+    /// A `GetInput`/`ForEach` requested an index past the end of the input source.
+    NoInputAt(usize),
+    /// The shared loop/recursion budget ran out: `Loop`/`WhileLoop`/`ForEach`
+    /// iterations and `Execute`/`CallFn` spawns all draw on it.
+    LimitExceeded,
+    /// A nested `Execute`/`CallFn` would recurse past `loop_limit` levels
+    /// deep. Carries the chain of calls that led here, innermost last.
+    RecursionLimit(Vec<String>),
+    /// A `GetInput` numeric value couldn't be parsed as a float.
+    ParseNumber(String),
+    /// `step`/`run` was called on a program that has already finished.
+    AlreadyFinished,
+    /// The program counter ran past the end of the instruction list.
+    NoInstructionAt(usize),
+    /// A `CallFn` named a function that hasn't been defined (or prelude'd).
+    UndefinedFn(String),
+    /// The program failed parse-time validation before anything ran.
+    Parse(ParseError),
+    /// A `TextOp` `D`ecode was given text that isn't valid Base64.
+    InvalidBase64(String),
+}
+
+impl fmt::Display for LbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LbError::UndefinedVar(var) => write!(f, "'{}' is not a valid variable name", var),
+            LbError::TypeMismatch { var, expected } => write!(f, "Variable {} is not a {}", var, expected),
+            LbError::InvalidOp { kind, op } => write!(f, "Invalid {} operator '{}'", kind, op),
+            LbError::NoInputAt(index) => write!(f, "No input at index {}", index),
+            LbError::LimitExceeded => write!(f, "loop limit exceeded"),
+            LbError::ParseNumber(text) => write!(f, "Could not parse '{}' as a number", text),
+            LbError::AlreadyFinished => write!(f, "Program is already finished."),
+            LbError::NoInstructionAt(index) => write!(f, "No command found at counter index {}", index),
+            LbError::UndefinedFn(name) => write!(f, "No function named '{}' is defined", name),
+            LbError::Parse(err) => write!(f, "{}", err),
+            LbError::InvalidBase64(text) => write!(f, "'{}' is not valid Base64", text),
+            LbError::RecursionLimit(stack) => write!(f, "Recursion limit exceeded: {}", stack.join(" -> ")),
+        }
+    }
+}
+
+/// An [LbError] plus where in the program's source text it happened, if
+/// known. This is what [Program::new] and [Program::run] actually return,
+/// so a failure in a long program points at the line that caused it
+/// instead of leaving the reader to guess.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LbDiagnostic {
+    pub error: LbError,
+    /// Absent when no source position applies, like a recursion-depth
+    /// refusal at construction time. Parse errors do carry one.
+    pub loc: Option<SourceLoc>,
+}
+
+impl fmt::Display for LbDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.loc {
+            Some(loc) => write!(f, "{}\n{}", self.error, loc),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
 /// A struct that represents a Letterbox program.
 /// It combines a list of parsed instructions and a Storage struct,
-/// executing each instruction in order.
-pub struct Program<'a> {
+/// executing each instruction in order. Generic over its [InputSource]
+/// `S`, so a program can be driven by a materialized `Vec<String>` of
+/// arguments or by a lazily-read stream, both equally.
+pub struct Program<'a, S: InputSource> {
     /// An ordered list of parsed instructions. See [LBT] for details.
     pub program_list: Vec<LBT>,
 
-    /// An integer that indicates the number of the next 
+    /// An integer that indicates the number of the next
     /// instruction to execute from the program list.
     program_counter: usize,
 
-    /// A reference to a [Storage] struct which will be modified 
+    /// A reference to a [Storage] struct which will be modified
     /// by the execution of this program.
     data: &'a mut Storage,
 
@@ -54,11 +135,12 @@ pub struct Program<'a> {
     /// The result of the last executed instruction.
     /// If this program is finished, this will be considered the
     /// result of the whole program.
-    pub result: Result<(), String>,
+    pub result: Result<(), LbError>,
 
-    /// Contains all input passed into this program from the environment
-    /// i.e. the command line.
-    pub input_vec: &'a Vec<String>,
+    /// Where `GetInput`/`ForEach` pull their values from. A `Vec<String>`
+    /// of command-line arguments by default, or anything else
+    /// implementing [InputSource].
+    pub input_vec: &'a mut S,
 
     /// A reference to a buffer to which output will be printed.
     pub output_buffer: &'a mut String,
@@ -66,18 +148,86 @@ pub struct Program<'a> {
     /// The maximum number of times a loop can run in this program.
     /// If a single loop exceeds this number, the program will crash.
     pub loop_limit: usize,
+
+    /// The number of `Loop`/`WhileLoop` iterations (and `Execute`/`CallFn`
+    /// spawns) still allowed before this program aborts with a limit
+    /// error. Starts at `loop_limit` and counts down as the program runs.
+    /// Shared (via `Rc<Cell<_>>`) with every program spawned by an
+    /// `Execute`/`CallFn` of this one, so the whole call tree draws down
+    /// one budget instead of each recursion level getting its own fresh
+    /// `loop_limit` to spend.
+    loops_remaining: Rc<Cell<usize>>,
+
+    /// How many nested `Execute`/`CallFn` spawns deep this program is.
+    /// The top-level program starts at depth 0; each spawn happens at
+    /// `depth + 1`, and is refused once that would exceed `loop_limit`
+    /// (the same configurable budget used for loop iterations).
+    depth: usize,
+
+    /// The chain of named `CallFn`/`Execute` invocations that led to this
+    /// program, innermost last. Pushed on entry and carried into
+    /// [LbError::RecursionLimit] if a deeper spawn is refused, so a
+    /// runaway recursion reports which calls produced it instead of
+    /// just failing.
+    call_stack: Vec<String>,
+
+    /// The program's original source text, kept around so a failing
+    /// instruction's [SourceLoc] can quote the line it happened on.
+    source: String,
+
+    /// The byte span of each entry in `program_list`, in the same order.
+    /// Used by [Program::locate] to find where a failing top-level
+    /// instruction came from.
+    spans: Vec<Range<usize>>,
 }
 
-impl<'a> Program<'a> {
+impl<'a, S: InputSource> Program<'a, S> {
     /// Create a new unexecuted program from the contents of
     /// the given lexer. Requires a reference to a Storage struct.
     pub fn new(lex: Lexer<LBT>,
         starting_data: &'a mut Storage,
-        inv: &'a Vec<String>,
+        inv: &'a mut S,
+        out: &'a mut String,
+        loop_limit: usize,
+    ) -> Result<Program<'a, S>, LbDiagnostic> {
+        let loops_remaining = Rc::new(Cell::new(loop_limit));
+        Self::new_at_depth(lex, starting_data, inv, out, loop_limit, 0, Vec::new(), loops_remaining)
+    }
+
+    /// Internal constructor used by `Execute`/`CallFn` to spawn a child
+    /// program one level deeper than its parent, carrying the chain of
+    /// calls that led here and the parent's shared `loops_remaining`
+    /// budget so the spawn draws from the same counter instead of
+    /// getting a fresh `loop_limit` of its own. Refuses to spawn past
+    /// `loop_limit` levels of recursion so mutually-recursive programs
+    /// fail gracefully, with a stack trace, instead of overflowing the
+    /// host stack.
+    fn new_at_depth(lex: Lexer<LBT>,
+        starting_data: &'a mut Storage,
+        inv: &'a mut S,
         out: &'a mut String,
         loop_limit: usize,
-    ) -> Result<Program<'a>, String> {
-        let plist: Vec<LBT> = lex.collect();
+        depth: usize,
+        call_stack: Vec<String>,
+        loops_remaining: Rc<Cell<usize>>,
+    ) -> Result<Program<'a, S>, LbDiagnostic> {
+        if depth > loop_limit {
+            return Err(LbDiagnostic { error: LbError::RecursionLimit(call_stack), loc: None });
+        }
+
+        let source = lex.source().to_string();
+        let mut lex = lex;
+        let mut tokens = Vec::new();
+        let mut spans = Vec::new();
+        while let Some(token) = lex.next() {
+            spans.push(lex.span());
+            tokens.push(token);
+        }
+
+        let plist: Vec<LBT> = parser::parse(tokens, &spans, &source).map_err(|err| {
+            LbDiagnostic { loc: Some(err.loc().clone()), error: LbError::Parse(err) }
+        })?;
+
         let prog = Program {
             program_list: plist,
             program_counter: 0,
@@ -86,29 +236,59 @@ impl<'a> Program<'a> {
             result: Ok(()),
             input_vec: inv,
             output_buffer: out,
-            loop_limit
+            loop_limit,
+            loops_remaining,
+            depth,
+            call_stack,
+            source,
+            spans,
         };
 
         Ok(prog)
     }
 
+    /// Attaches the source location of the instruction at `program_counter`
+    /// to a raw runtime error, for [Program::run] to return. Falls back to
+    /// no location if the counter has run past the end of `spans`, which
+    /// shouldn't happen in practice but costs nothing to guard against.
+    fn locate(&self, error: LbError) -> LbDiagnostic {
+        let loc = self.spans.get(self.program_counter)
+            .map(|span| SourceLoc::new(&self.source, span.clone()));
+        LbDiagnostic { error, loc }
+    }
+
+    /// Consumes one unit of the shared loop/recursion budget.
+    /// Returns an error once the budget is exhausted instead of
+    /// letting a loop or recursive `Execute` run forever.
+    fn consume_budget(&mut self) -> Result<(), LbError> {
+        let remaining = self.loops_remaining.get();
+        if remaining == 0 {
+            return Err(LbError::LimitExceeded);
+        }
+        self.loops_remaining.set(remaining - 1);
+        Ok(())
+    }
+
     /// Run the program until it finishes.
-    pub fn run(&mut self) -> Result<(), String> {
+    pub fn run(&mut self) -> Result<(), LbDiagnostic> {
         while !self.finished {
             let step_result = self.step();
             if let Err(_) = step_result {
                 self.finished = true;
-                return self.result.clone();
+                break;
             }
         }
 
-        return self.result.clone();
+        match self.result.clone() {
+            Ok(()) => Ok(()),
+            Err(err) => Err(self.locate(err)),
+        }
     }
 
     /// Run the next instruction as indicated by the program counter.
-    pub fn step(&mut self) -> Result<(), String> {
+    pub fn step(&mut self) -> Result<(), LbError> {
         if self.finished {
-            return Err(String::from("Program is already finished."));
+            return Err(LbError::AlreadyFinished);
         }
 
         // Get the instruction at the next position in the program.
@@ -118,14 +298,14 @@ impl<'a> Program<'a> {
             let command = &token.clone();
 
             // Evaluate the instruction
-            let step_result: Result<(), String> = self.evaluate(command);
+            let step_result: Result<(), LbError> = self.evaluate(command);
 
             // Set the current result to the most recent instruction's result
             self.result = step_result;
 
             // If there is an error, don't execute any further.
-            if let Err(msg) = &self.result {
-                return Err(msg.to_string());
+            if let Err(err) = &self.result {
+                return Err(err.clone());
             }
 
             // Increment the program counter
@@ -134,15 +314,15 @@ impl<'a> Program<'a> {
             Ok(())
         }
         else {
-            return Err(format!("No command found at counter index {}", self.program_counter));
+            return Err(LbError::NoInstructionAt(self.program_counter));
         }
     }
 
     /// Runs an instruction and returns a result.
     /// This is the main location where parser tokens are mapped to
-    /// execution implementations. Side effects abound as these implementations 
+    /// execution implementations. Side effects abound as these implementations
     /// can and will manipulate this program's data storage.
-    fn evaluate(&mut self, command: &LBT) -> Result<(), String> {
+    fn evaluate(&mut self, command: &LBT) -> Result<(), LbError> {
         match command {
 
             // Sa4
@@ -162,7 +342,7 @@ impl<'a> Program<'a> {
 
             // Pa
             PrintVar(var_name) => {
-                let print_str = self.data.get_var(*var_name).expect("Could not get variable.");
+                let print_str = self.data.get_var(*var_name)?;
                 self.output_buffer.push_str(format!("{}", print_str).as_str());
                 Ok(())
             },
@@ -175,19 +355,11 @@ impl<'a> Program<'a> {
 
             // MAcab
             MathOp((op, target, a, b)) => {
-                let Val::Number(n_a) = self.data
-                    .get_var(*a)
-                    .expect(&format!("M: Could not get variable {a}"))
-                    .to_owned() 
-                else {
-                    return Err(format!("M: Variable {a} is not a number"));
+                let Val::Number(n_a) = self.data.get_var(*a)?.to_owned() else {
+                    return Err(LbError::TypeMismatch { var: *a, expected: "number" });
                 };
-                let Val::Number(n_b) = self.data
-                    .get_var(*b)
-                    .expect(&format!("M: Could not get variable {b}"))
-                    .to_owned() 
-                else {
-                    return Err(format!("M: Variable {b} is not a number"));
+                let Val::Number(n_b) = self.data.get_var(*b)?.to_owned() else {
+                    return Err(LbError::TypeMismatch { var: *b, expected: "number" });
                 };
 
                 // compute result
@@ -201,7 +373,7 @@ impl<'a> Program<'a> {
                     'G' => if n_a > n_b { 1.0 } else { 0.0 },       // greater than
                     'L' => if n_a < n_b { 1.0 } else { 0.0 },       // less than
                     _ => {
-                        return Err(format!("M: Invalid op {}", op));
+                        return Err(LbError::InvalidOp { kind: "math", op: *op });
                     },
                 };
                 // save result to storage
@@ -210,14 +382,8 @@ impl<'a> Program<'a> {
 
             // BAcab
             BoolOp((op, target, a, b)) => {
-                let b_a = self.data
-                    .var_as_bool(*a)
-                    .expect(&format!("B: Could not get variable {a}"))
-                    .to_owned();
-                let b_b = self.data
-                    .var_as_bool(*b)
-                    .expect(&format!("B: Could not get variable {b}"))
-                    .to_owned();
+                let b_a = self.data.var_as_bool(*a)?;
+                let b_b = self.data.var_as_bool(*b)?;
 
                 // compute result
                 let result = match op {
@@ -226,7 +392,7 @@ impl<'a> Program<'a> {
                     'O' => if b_a || b_b { 1.0 } else { 0.0 },                       // or
                     'X' => if (b_a && !b_b) || (!b_a && b_b) { 1.0 } else { 0.0 }, // xor
                     _ => {
-                        return Err(format!("B: Invalid op {}", op));
+                        return Err(LbError::InvalidOp { kind: "bool", op: *op });
                     },
                 };
                 // save result to storage
@@ -240,10 +406,7 @@ impl<'a> Program<'a> {
 
             // Na
             Negate(var_name) => {
-                let current = self.data
-                    .var_as_bool(*var_name)
-                    .expect(&format!("Could not get variable {var_name}"))
-                    .to_owned();
+                let current = self.data.var_as_bool(*var_name)?;
                 if current {
                     return self.data.reset_var(*var_name);
                 }
@@ -258,23 +421,18 @@ impl<'a> Program<'a> {
             },
 
             // LaX
-            Loop((times, subcommand)) => {
+            Loop((times, body)) => {
                 // get number of loops
-                let Val::Number(t) = self.data
-                    .get_var(*times)
-                    .expect(&format!("L: Could not get variable {times}"))
-                    .to_owned() 
-                else {
-                    return Err(format!("L: Variable {times} is not a number"));
+                let Val::Number(t) = self.data.get_var(*times)?.to_owned() else {
+                    return Err(LbError::TypeMismatch { var: *times, expected: "number" });
                 };
 
                 let mut loops = t.floor() as i64;
-                
-                // execute subcommand that many times
+
+                // execute the body that many times
                 while loops > 0 {
-                    if let Err(msg) = self.evaluate(subcommand) {
-                        return Err(msg);
-                    }
+                    self.consume_budget()?;
+                    self.evaluate_block(body)?;
                     loops -= 1;
                 }
 
@@ -282,39 +440,29 @@ impl<'a> Program<'a> {
             },
 
             // IaX
-            IfStatement((cond, subcommand)) => {
+            IfStatement((cond, body)) => {
                 // get condition as bool
-                let c = self.data
-                    .var_as_bool(*cond)
-                    .expect(&format!("I: Could not get variable {cond}"))
-                    .to_owned();
-                
-                // execute subcommand if condition is true
+                let c = self.data.var_as_bool(*cond)?;
+
+                // execute the body if condition is true
                 if c {
-                    return self.evaluate(subcommand);
+                    return self.evaluate_block(body);
                 }
 
                 Ok(())
             },
 
             // WaX
-            WhileLoop((cond, subcommand)) => {
+            WhileLoop((cond, body)) => {
                 // get condition as bool
-                let mut c = self.data
-                    .var_as_bool(*cond)
-                    .expect(&format!("W: Could not get variable {cond}"))
-                    .to_owned();
-                
-                // execute subcommand until condition evaluates false
+                let mut c = self.data.var_as_bool(*cond)?;
+
+                // execute the body until condition evaluates false
                 while c {
-                    if let Err(msg) = self.evaluate(subcommand) {
-                        return Err(msg);
-                    }
+                    self.consume_budget()?;
+                    self.evaluate_block(body)?;
 
-                    c = self.data
-                    .var_as_bool(*cond)
-                    .expect(&format!("W: Could not get variable {cond}"))
-                    .to_owned();
+                    c = self.data.var_as_bool(*cond)?;
                 }
 
                 Ok(())
@@ -323,13 +471,12 @@ impl<'a> Program<'a> {
             // GXa1
             GetInput((op, var, num)) => {
                 let index = num.floor() as usize;
-                let Some(input) = self.input_vec.get(index) else {
-                    return Err(format!("G: no input at index {num}"))
+                let Some(input_item) = self.input_vec.get(index) else {
+                    return Err(LbError::NoInputAt(index));
                 };
-                let input_item = input.to_string();
 
                 if !storage::is_var(var) {
-                    return Err(format!("G: character {var} is not a variable name"));
+                    return Err(LbError::UndefinedVar(*var));
                 }
                 match *op {
                     'N' => {
@@ -337,50 +484,202 @@ impl<'a> Program<'a> {
                             self.data.set_var(*var, &Val::Number(val))
                         }
                         else {
-                            Err(format!("G: Could not parse input into number: {input_item}"))
+                            Err(LbError::ParseNumber(input_item))
                         }
                     },
                     'S' => {
                         self.data.set_var(*var, &Val::Text(String::from(input_item)))
                     },
-                    _ => Err(format!("G: invalid operation {op}")),
+                    _ => Err(LbError::InvalidOp { kind: "input", op: *op }),
                 }
             },
 
+            // ENiX / ESiX
+            ForEach((op, index_var, subcommand)) => {
+                if !storage::is_var(index_var) {
+                    return Err(LbError::UndefinedVar(*index_var));
+                }
+
+                // pulled one index at a time (rather than iterating a
+                // materialized `Vec`), so this also drains a lazy InputSource
+                // in order instead of requiring it all up front
+                let mut i = 0;
+                while let Some(item) = self.input_vec.get(i) {
+                    self.consume_budget()?;
+
+                    match *op {
+                        'N' => {
+                            let Some(val) = item.parse::<f64>().ok() else {
+                                return Err(LbError::ParseNumber(item));
+                            };
+                            self.data.set_var('a', &Val::Number(val))?;
+                        },
+                        'S' => {
+                            self.data.set_var('a', &Val::Text(item))?;
+                        },
+                        _ => return Err(LbError::InvalidOp { kind: "foreach", op: *op }),
+                    }
+                    self.data.set_var(*index_var, &Val::Number(i as f64))?;
+
+                    self.evaluate(subcommand)?;
+                    i += 1;
+                }
+
+                Ok(())
+            },
+
+            // YCdab / YLda / YSdabc / YIdab
+            StrOp((op, args)) => {
+                match op {
+                    'C' => {
+                        let (dst, a, b) = (args[0], args[1], args[2]);
+                        let Val::Text(s_a) = self.data.get_var(a)?.to_owned() else {
+                            return Err(LbError::TypeMismatch { var: a, expected: "string" });
+                        };
+                        let Val::Text(s_b) = self.data.get_var(b)?.to_owned() else {
+                            return Err(LbError::TypeMismatch { var: b, expected: "string" });
+                        };
+                        self.data.set_var(dst, &Val::Text(format!("{}{}", s_a, s_b)))
+                    },
+                    'L' => {
+                        let (dst, src) = (args[0], args[1]);
+                        let Val::Text(s) = self.data.get_var(src)?.to_owned() else {
+                            return Err(LbError::TypeMismatch { var: src, expected: "string" });
+                        };
+                        self.data.set_var(dst, &Val::Number(s.chars().count() as f64))
+                    },
+                    'S' => {
+                        let (dst, src, start_var, len_var) = (args[0], args[1], args[2], args[3]);
+                        let Val::Text(s) = self.data.get_var(src)?.to_owned() else {
+                            return Err(LbError::TypeMismatch { var: src, expected: "string" });
+                        };
+                        let Val::Number(start) = self.data.get_var(start_var)?.to_owned() else {
+                            return Err(LbError::TypeMismatch { var: start_var, expected: "number" });
+                        };
+                        let Val::Number(len) = self.data.get_var(len_var)?.to_owned() else {
+                            return Err(LbError::TypeMismatch { var: len_var, expected: "number" });
+                        };
+                        let start_idx = start.floor().max(0.0) as usize;
+                        let take_count = len.floor().max(0.0) as usize;
+                        let substr: String = s.chars().skip(start_idx).take(take_count).collect();
+                        self.data.set_var(dst, &Val::Text(substr))
+                    },
+                    'I' => {
+                        let (dst, haystack_var, needle_var) = (args[0], args[1], args[2]);
+                        let Val::Text(haystack) = self.data.get_var(haystack_var)?.to_owned() else {
+                            return Err(LbError::TypeMismatch { var: haystack_var, expected: "string" });
+                        };
+                        let Val::Text(needle) = self.data.get_var(needle_var)?.to_owned() else {
+                            return Err(LbError::TypeMismatch { var: needle_var, expected: "string" });
+                        };
+                        // -1 is the not-found sentinel, since a valid index is always >= 0
+                        let index = haystack.find(&needle)
+                            .map(|byte_idx| haystack[..byte_idx].chars().count() as f64)
+                            .unwrap_or(-1.0);
+                        self.data.set_var(dst, &Val::Number(index))
+                    },
+                    _ => Err(LbError::InvalidOp { kind: "string", op: *op }),
+                }
+            },
+
+            // TUda / TLda / TRda / TNda / TSdabc / TEda / TDda
+            TextOp((op, args)) => {
+                self.data.text_op(*op, args)
+            },
+
             // Xzacbd
             Execute((fn_var, argmap)) => {
                 // validate argmap
                 for c in argmap.chars() {
                     if !storage::is_var(&c) {
-                        return Err(format!("X: Character {c} is not a variable name"));
+                        return Err(LbError::UndefinedVar(c));
                     }
                 }
 
                 // get string to execute
-                let Val::Text(prog) = self.data
-                    .get_var(*fn_var)
-                    .expect(&format!("X: Could not get variable {fn_var}"))
-                    .to_owned() 
-                else {
-                    return Err(format!("X: Variable {fn_var} is not a string"));
+                let Val::Text(prog) = self.data.get_var(*fn_var)?.to_owned() else {
+                    return Err(LbError::TypeMismatch { var: *fn_var, expected: "string" });
                 };
 
+                // each spawn consumes from this program's shared budget too,
+                // so a string that keeps re-Executing itself can't spin forever
+                self.consume_budget()?;
+
                 // substitute provided arguments
                 let prog_with_params = Self::apply_argmap(prog, argmap.to_string());
 
                 // create lexer to parse the string
                 let sub_lex = LBT::lexer(&prog_with_params);
-                // create new program using this program's params
-                let sub_program = Program::new(
+                // create new program one recursion level deeper than this one
+                let mut stack = self.call_stack.clone();
+                stack.push(format!("<execute:{}>", fn_var));
+                let sub_program = Program::new_at_depth(
                     sub_lex,
-                    self.data, 
-                    self.input_vec, 
-                    self.output_buffer, 
-                    self.loop_limit.clone());
+                    self.data,
+                    self.input_vec,
+                    self.output_buffer,
+                    self.loop_limit,
+                    self.depth + 1,
+                    stack,
+                    self.loops_remaining.clone());
+
+                // A sub-program has its own source text, so its location
+                // (if any) wouldn't mean anything in this program's
+                // source; drop it and let the caller's `step` attach its
+                // own location (that of this `Execute`) if this bubbles up.
+                match sub_program {
+                    Ok(mut program) => program.run().map_err(|diag| diag.error),
+                    Err(diag) => Err(diag.error),
+                }
+            },
+
+            // Dgreet'P\'Hello\''
+            DefineFn((name, body)) => {
+                self.data.define_fn(name.clone(), body.clone())
+            },
 
+            // Kgreet.
+            CallFn((name, argmap)) => {
+                // validate argmap
+                for c in argmap.chars() {
+                    if !storage::is_var(&c) {
+                        return Err(LbError::UndefinedVar(c));
+                    }
+                }
+
+                // look up the registered function body
+                let Some(body) = self.data.get_fn(name) else {
+                    return Err(LbError::UndefinedFn(name.clone()));
+                };
+                let body = body.clone();
+
+                // each call consumes from this program's shared budget too
+                self.consume_budget()?;
+
+                // substitute provided arguments
+                let body_with_params = Self::apply_argmap(body, argmap.to_string());
+
+                // create lexer to parse the function body
+                let sub_lex = LBT::lexer(&body_with_params);
+                // create new program one recursion level deeper than this one,
+                // with this call pushed onto the stack trace
+                let mut stack = self.call_stack.clone();
+                stack.push(name.clone());
+                let sub_program = Program::new_at_depth(
+                    sub_lex,
+                    self.data,
+                    self.input_vec,
+                    self.output_buffer,
+                    self.loop_limit,
+                    self.depth + 1,
+                    stack,
+                    self.loops_remaining.clone());
+
+                // Same reasoning as `Execute`: drop the sub-program's own
+                // location and let it be re-attached at this call site.
                 match sub_program {
-                    Ok(mut program) => program.run(),
-                    Err(msg) => Err(msg),
+                    Ok(mut program) => program.run().map_err(|diag| diag.error),
+                    Err(diag) => Err(diag.error),
                 }
             },
 
@@ -390,8 +689,21 @@ impl<'a> Program<'a> {
                 Ok(())
             },
 
-            _ => Err(format!("Unrecognized instruction at counter index {}", self.program_counter)),
+            // Unreachable in practice: `parser::parse` rejects every `Error`
+            // token before a program is ever constructed. Kept for match
+            // exhaustiveness over the rest of [LBT].
+            _ => Err(LbError::NoInstructionAt(self.program_counter)),
+        }
+    }
+
+    /// Runs every instruction in a `Loop`/`IfStatement`/`WhileLoop` body in
+    /// order, stopping at the first error. Bodies are either a single
+    /// legacy subcommand or a bracket-delimited sequence of several.
+    fn evaluate_block(&mut self, body: &Vec<LBT>) -> Result<(), LbError> {
+        for command in body {
+            self.evaluate(command)?;
         }
+        Ok(())
     }
 
     /// Increment the program counter, which determines which
@@ -413,8 +725,9 @@ impl<'a> Program<'a> {
     /// with 'd'. This does not affect hardcoded strings being saved or printed in the program.
     fn apply_argmap(raw: String, argmap: String) -> String {
 
-        // use this regex to match quotes
-        let rx_quotes = Regex::new(r"'[^']*'").expect("Invalid regex");
+        // use this regex to match quotes; `([^'\\]|\\.)*` skips over escaped
+        // quotes (`\'`) so a literal quote inside a string doesn't end it early
+        let rx_quotes = Regex::new(r"'([^'\\]|\\.)*'").expect("Invalid regex");
 
         // remove all quoted strings from the text
         let quoted_strings = rx_quotes.find_iter(&raw);
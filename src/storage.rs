@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use crate::program::Val;
+use crate::program::{LbError, Val};
 
 // const VALID_FNS:  &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
 const VALID_VARS: &str = "abcdefghijklmnopqrstuvwxyz";
@@ -18,54 +18,219 @@ pub fn is_var(c: &char) -> bool {
 
 pub struct Storage {
     data: HashMap<char, Val>,
+    functions: HashMap<String, String>,
 }
 
 impl Storage {
     pub fn new() -> Storage {
-        let store = Storage {
+        let mut store = Storage {
             data: HashMap::new(),
+            functions: HashMap::new(),
         };
         // for var_name in VALID_VARS.chars() {
         //     store.data.insert(var_name, Val::zero());
         // }
+        register_prelude(&mut store);
         return store;
     }
 
-    pub fn get_var(&mut self, var_name: char) -> Option<&Val> {
+    /// Registers a named subprogram (`Dname'...'`) so it can later be
+    /// invoked with `CallFn`.
+    pub fn define_fn(&mut self, name: String, body: String) -> Result<(), LbError> {
+        self.functions.insert(name, body);
+        Ok(())
+    }
+
+    /// Looks up a previously-defined (or prelude'd) function body by name.
+    pub fn get_fn(&self, name: &str) -> Option<&String> {
+        self.functions.get(name)
+    }
+
+    pub fn get_var(&mut self, var_name: char) -> Result<&Val, LbError> {
         if !is_var(&var_name) {
-            return None;
+            return Err(LbError::UndefinedVar(var_name));
         }
         let val = self.data.entry(var_name)
             .or_insert(Val::zero());
-        Some(val)
+        Ok(val)
     }
 
-    pub fn set_var(&mut self, var_name: char, new_value: &Val) -> Result<(), String> {
+    pub fn set_var(&mut self, var_name: char, new_value: &Val) -> Result<(), LbError> {
         self.data.insert(var_name, (*new_value).clone());
         Ok(())
     }
 
-    pub fn reset_var(&mut self, var_name: char) -> Result<(), String> {
+    pub fn reset_var(&mut self, var_name: char) -> Result<(), LbError> {
         self.data.remove(&var_name);
         Ok(())
     }
 
-    pub fn reset_all(&mut self) -> Result<(), String> {
+    pub fn reset_all(&mut self) -> Result<(), LbError> {
         self.data.clear();
         Ok(())
     }
 
-    pub fn copy(&mut self, from_var: char, to_var: char) -> Result<(), String> {
-        let x = self.get_var(from_var).expect("Couldn't find variable");
-        let y = (*x).clone();
+    pub fn copy(&mut self, from_var: char, to_var: char) -> Result<(), LbError> {
+        let y = self.get_var(from_var)?.clone();
         self.set_var(to_var, &y)
     }
 
-    pub fn var_as_bool(&mut self, var_name: char) -> Option<bool> {
-        let x = self.get_var(var_name).expect("Couldn't find variable");
-        return match x {
-            Val::Number(n) => Some(*n != 0.0),
-            Val::Text(_) => Some(true),
-        };
+    pub fn var_as_bool(&mut self, var_name: char) -> Result<bool, LbError> {
+        let x = self.get_var(var_name)?;
+        Ok(match x {
+            Val::Number(n) => *n != 0.0,
+            Val::Text(_) => true,
+        })
+    }
+
+    /// Returns every currently-set variable and its value, sorted by name.
+    /// Used by the REPL's `:vars` meta-command to inspect live state.
+    pub fn vars(&self) -> Vec<(char, &Val)> {
+        let mut entries: Vec<(char, &Val)> = self.data.iter()
+            .map(|(name, val)| (*name, val))
+            .collect();
+        entries.sort_by_key(|(name, _)| *name);
+        entries
+    }
+
+    /// Performs a `TextOp` (`T<op><dst><args...>`), transforming a
+    /// `Val::Text` operand and writing the result back. Lives here rather
+    /// than in `program::evaluate` (unlike `MathOp`/`BoolOp`/`StrOp`)
+    /// because every op just reads and writes `Storage`, with no
+    /// control-flow or I/O involved.
+    ///
+    /// `U`ppercase / `L`owercase / `R`everse: `dst` := the transform of
+    /// `src`. `N`: `dst` := the character length of `src`. `S`ubstring:
+    /// `dst` := the substring of `src` starting at (numeric) `start` for
+    /// (numeric) `len` characters. `E`ncode / `D`ecode: `dst` := the
+    /// standard-alphabet Base64 encoding (or decoding) of `src`.
+    pub fn text_op(&mut self, op: char, args: &Vec<char>) -> Result<(), LbError> {
+        match op {
+            'U' | 'L' | 'R' | 'N' | 'E' | 'D' => {
+                let (dst, src) = (args[0], args[1]);
+                let Val::Text(s) = self.get_var(src)?.to_owned() else {
+                    return Err(LbError::TypeMismatch { var: src, expected: "string" });
+                };
+                let result = match op {
+                    'U' => Val::Text(s.to_uppercase()),
+                    'L' => Val::Text(s.to_lowercase()),
+                    'R' => Val::Text(s.chars().rev().collect()),
+                    'N' => Val::Number(s.chars().count() as f64),
+                    'E' => Val::Text(base64_encode(&s)),
+                    'D' => Val::Text(base64_decode(&s)?),
+                    _ => unreachable!(),
+                };
+                self.set_var(dst, &result)
+            },
+            'S' => {
+                let (dst, src, start_var, len_var) = (args[0], args[1], args[2], args[3]);
+                let Val::Text(s) = self.get_var(src)?.to_owned() else {
+                    return Err(LbError::TypeMismatch { var: src, expected: "string" });
+                };
+                let Val::Number(start) = self.get_var(start_var)?.to_owned() else {
+                    return Err(LbError::TypeMismatch { var: start_var, expected: "number" });
+                };
+                let Val::Number(len) = self.get_var(len_var)?.to_owned() else {
+                    return Err(LbError::TypeMismatch { var: len_var, expected: "number" });
+                };
+                let start_idx = start.floor().max(0.0) as usize;
+                let take_count = len.floor().max(0.0) as usize;
+                let substr: String = s.chars().skip(start_idx).take(take_count).collect();
+                self.set_var(dst, &Val::Text(substr))
+            },
+            _ => Err(LbError::InvalidOp { kind: "text", op }),
+        }
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `input`'s UTF-8 bytes as standard-alphabet Base64: every group
+/// of 3 input bytes becomes 4 output characters, padding a short final
+/// group with `=` so the output length is always a multiple of 4.
+fn base64_encode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = String::with_capacity((bytes.len() + 2) / 3 * 4);
+
+    for chunk in bytes.chunks(3) {
+        let group = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | (*chunk.get(2).unwrap_or(&0) as u32);
+
+        // a short final chunk still produces 4 characters, but everything
+        // past `chunk.len() + 1` real sextets is padding instead
+        let real_chars = chunk.len() + 1;
+        for (i, shift) in [18, 12, 6, 0].iter().enumerate() {
+            if i < real_chars {
+                out.push(BASE64_ALPHABET[((group >> shift) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
     }
+
+    out
+}
+
+/// Decodes standard-alphabet Base64 text back to the original bytes,
+/// erroring on a length that isn't a multiple of 4, a non-alphabet
+/// character, padding that isn't confined to the end of the final group,
+/// padding that appears in any group before the last, or decoded bytes
+/// that aren't valid UTF-8.
+fn base64_decode(input: &str) -> Result<String, LbError> {
+    let invalid = || LbError::InvalidBase64(input.to_string());
+
+    if input.len() % 4 != 0 {
+        return Err(invalid());
+    }
+
+    let chars: Vec<char> = input.chars().collect();
+    let chunks: Vec<&[char]> = chars.chunks(4).collect();
+    let mut bytes = Vec::with_capacity(chars.len() / 4 * 3);
+
+    for (chunk_idx, chunk) in chunks.iter().enumerate() {
+        let mut sextets = [0u32; 4];
+        let mut pad_count = 0;
+
+        for (i, c) in chunk.iter().enumerate() {
+            if *c == '=' {
+                pad_count += 1;
+            } else {
+                // padding may only trail the real characters, never precede them
+                if pad_count > 0 {
+                    return Err(invalid());
+                }
+                sextets[i] = BASE64_ALPHABET.iter().position(|b| *b as char == *c)
+                    .ok_or_else(invalid)? as u32;
+            }
+        }
+        if pad_count > 2 {
+            return Err(invalid());
+        }
+        // padding may only appear in the very last group of the input,
+        // never in an earlier one (e.g. "QQ==AAAA" must be rejected
+        // instead of silently decoding past the padded chunk)
+        if pad_count > 0 && chunk_idx != chunks.len() - 1 {
+            return Err(invalid());
+        }
+
+        let group = sextets[0] << 18 | sextets[1] << 12 | sextets[2] << 6 | sextets[3];
+        let group_bytes = [(group >> 16) as u8, (group >> 8) as u8, group as u8];
+        bytes.extend_from_slice(&group_bytes[..3 - pad_count]);
+    }
+
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// Registers the built-in helper functions every `Storage` starts with,
+/// by convention keyed off variable `a` the way `ForEach` and `Execute`
+/// single-parameter idioms already do. Users can still shadow these with
+/// their own `DefineFn` of the same name.
+fn register_prelude(store: &mut Storage) {
+    // double: a = a + a
+    store.functions.insert(String::from("double"), String::from("MAaaa"));
+    // inc: a = a + 1
+    store.functions.insert(String::from("inc"), String::from("Sb1 MAaab"));
+    // zero: a = 0
+    store.functions.insert(String::from("zero"), String::from("Ra"));
 }
\ No newline at end of file